@@ -1,17 +1,78 @@
 #![feature(box_patterns)]
 
+use log::{debug, trace};
 use pyo3::prelude::*;
-use pyo3::exceptions;
 use pyo3::types::PyTuple;
+use pyo3::wrap_pymodule;
 
 use ::typeset::{self as native};
 
 mod parser;
 
+// Records the combinator applied while a layout is *built* (`grp`/`nest`/
+// `pack`/`comp`/`line`), not a decision `native::compile`/`native::render`
+// made while solving it — the native crate exposes no solver callback to
+// hook, so "which node broke" isn't observable directly. `render_verbose`
+// makes up for that where it can: it appends the *measured* width of each
+// rendered line (real solve output) to the same trace, so a caller at least
+// sees which lines the solver actually produced versus the requested width.
+//
+// The trace lives on the `Layout`/`Document` value itself (not a shared
+// sink), so it always reflects the specific tree being built or rendered,
+// and draining one document's trace never affects another's.
+fn record(trace: &mut Vec<String>, event: String) {
+  trace!("{}", event);
+  trace.push(event);
+}
+
 #[pyclass]
 #[derive(Debug, Clone)]
 struct Layout {
-  native: Box<native::Layout>
+  native: Box<native::Layout>,
+  trace: Vec<String>
+}
+
+fn leaf(native: Box<native::Layout>) -> Layout {
+  Layout { native, trace: Vec::new() }
+}
+
+fn combine_unary(layout: Layout, apply: impl FnOnce(Box<native::Layout>) -> Box<native::Layout>, event: Option<String>) -> Layout {
+  let mut trace = layout.trace;
+  if let Some(event) = event {
+    record(&mut trace, event);
+  }
+  Layout { native: apply(layout.native), trace }
+}
+
+fn do_grp(layout: Layout) -> Layout {
+  let event = format!("grp: marking {} as a breakable group", layout.native);
+  combine_unary(layout, native::grp, Some(event))
+}
+
+fn do_nest(layout: Layout) -> Layout {
+  let event = format!("nest: indenting {}", layout.native);
+  combine_unary(layout, native::nest, Some(event))
+}
+
+fn do_pack(layout: Layout) -> Layout {
+  let event = format!("pack: packing {}", layout.native);
+  combine_unary(layout, native::pack, Some(event))
+}
+
+fn do_line(left: Layout, right: Layout) -> Layout {
+  let event = format!("line: {} then a hard break then {}", left.native, right.native);
+  let mut trace = left.trace;
+  trace.extend(right.trace);
+  record(&mut trace, event);
+  Layout { native: native::line(left.native, right.native), trace }
+}
+
+fn do_comp(left: Layout, right: Layout, pad: bool, fix: bool) -> Layout {
+  let event = format!("comp: {} then {} (pad={}, fix={})", left.native, right.native, pad, fix);
+  let mut trace = left.trace;
+  trace.extend(right.trace);
+  record(&mut trace, event);
+  Layout { native: native::comp(left.native, right.native, pad, fix), trace }
 }
 
 #[pymethods]
@@ -19,12 +80,89 @@ impl Layout {
   fn __repr__(&self) -> String {
     format!("{}", self.native)
   }
+
+  fn __add__(&self, other: &Bound<'_, PyAny>) -> PyResult<Layout> {
+    let other = coerce(other)?;
+    Ok(do_comp(self.clone(), other, true, false))
+  }
+
+  fn __radd__(&self, other: &Bound<'_, PyAny>) -> PyResult<Layout> {
+    let other = coerce(other)?;
+    Ok(do_comp(other, self.clone(), true, false))
+  }
+
+  fn __and__(&self, other: &Bound<'_, PyAny>) -> PyResult<Layout> {
+    let other = coerce(other)?;
+    Ok(do_comp(self.clone(), other, false, false))
+  }
+
+  fn __rand__(&self, other: &Bound<'_, PyAny>) -> PyResult<Layout> {
+    let other = coerce(other)?;
+    Ok(do_comp(other, self.clone(), false, false))
+  }
+
+  fn __truediv__(&self, other: &Bound<'_, PyAny>) -> PyResult<Layout> {
+    let other = coerce(other)?;
+    Ok(do_line(self.clone(), other))
+  }
+
+  fn __rtruediv__(&self, other: &Bound<'_, PyAny>) -> PyResult<Layout> {
+    let other = coerce(other)?;
+    Ok(do_line(other, self.clone()))
+  }
+
+  fn __or__(&self, other: &Bound<'_, PyAny>) -> PyResult<Layout> {
+    let other = coerce(other)?;
+    Ok(do_grp(do_line(self.clone(), other)))
+  }
+
+  fn __ror__(&self, other: &Bound<'_, PyAny>) -> PyResult<Layout> {
+    let other = coerce(other)?;
+    Ok(do_grp(do_line(other, self.clone())))
+  }
+
+  fn fix(&self) -> PyResult<Layout> {
+    Ok(combine_unary(self.clone(), native::fix, None))
+  }
+
+  fn grp(&self) -> PyResult<Layout> {
+    Ok(do_grp(self.clone()))
+  }
+
+  fn seq(&self) -> PyResult<Layout> {
+    Ok(combine_unary(self.clone(), native::seq, None))
+  }
+
+  fn nest(&self) -> PyResult<Layout> {
+    Ok(do_nest(self.clone()))
+  }
+
+  fn pack(&self) -> PyResult<Layout> {
+    Ok(do_pack(self.clone()))
+  }
+
+  fn compile(&self, py: Python) -> PyResult<Document> {
+    let Layout { native, trace } = self.clone();
+    debug!("compile: solving layout {}", native);
+    let doc = py.allow_threads(|| native::compile(native));
+    debug!("compile: produced document {}", doc);
+    Ok(Document { native: doc, trace })
+  }
+}
+
+fn coerce(obj: &Bound<'_, PyAny>) -> PyResult<Layout> {
+  if let Ok(layout) = obj.extract::<Layout>() {
+    return Ok(layout);
+  }
+  let data = obj.extract::<String>()?;
+  Ok(leaf(native::text(data)))
 }
 
 #[pyclass]
 #[derive(Debug, Clone)]
 struct Document {
-  native: Box<native::Doc>
+  native: Box<native::Doc>,
+  trace: Vec<String>
 }
 
 #[pymethods]
@@ -32,51 +170,74 @@ impl Document {
   fn __repr__(&self) -> String {
     format!("{}", self.native)
   }
+
+  fn render(&self, py: Python, tab: usize, width: usize) -> PyResult<String> {
+    let native = self.native.clone();
+    debug!("render: doc={} tab={} width={}", native, tab, width);
+    let output = py.allow_threads(|| native::render(native, tab, width));
+    debug!("render: produced {} byte(s) of output", output.len());
+    Ok(output)
+  }
+
+  fn print(&self) -> PyResult<String> {
+    Ok(format!("{}", self.native))
+  }
+}
+
+// Appends the measured width of each rendered line to `trace` — real output
+// of the solve, even though we can't attribute a line back to the specific
+// `grp`/`pack` node responsible for it.
+fn measure_lines(trace: &mut Vec<String>, output: &str, width: usize) {
+  for (number, line) in output.lines().enumerate() {
+    let measured = line.chars().count();
+    let fits = if measured > width { "exceeds" } else { "fits within" };
+    record(trace, format!("render: line {} measured width {} ({} limit {})", number, measured, fits, width));
+  }
 }
 
 #[pyfunction]
 fn null() -> PyResult<Layout> {
-  Ok(Layout { native: native::null() })
+  Ok(leaf(native::null()))
 }
 
 #[pyfunction]
 fn text(data: String) -> PyResult<Layout> {
-  Ok(Layout { native: native::text(data) })
+  Ok(leaf(native::text(data)))
 }
 
 #[pyfunction]
 fn fix(layout: Layout) -> PyResult<Layout> {
-  Ok(Layout { native: native::fix(layout.native) })
+  Ok(combine_unary(layout, native::fix, None))
 }
 
 #[pyfunction]
 fn grp(layout: Layout) -> PyResult<Layout> {
-  Ok(Layout { native: native::grp(layout.native) })
+  Ok(do_grp(layout))
 }
 
 #[pyfunction]
 fn seq(layout: Layout) -> PyResult<Layout> {
-  Ok(Layout { native: native::seq(layout.native) })
+  Ok(combine_unary(layout, native::seq, None))
 }
 
 #[pyfunction]
 fn nest(layout: Layout) -> PyResult<Layout> {
-  Ok(Layout { native: native::nest(layout.native) })
+  Ok(do_nest(layout))
 }
 
 #[pyfunction]
 fn pack(layout: Layout) -> PyResult<Layout> {
-  Ok(Layout { native: native::pack(layout.native) })
+  Ok(do_pack(layout))
 }
 
 #[pyfunction]
 fn line(left: Layout, right: Layout) -> PyResult<Layout> {
-  Ok(Layout { native: native::line(left.native, right.native) })
+  Ok(do_line(left, right))
 }
 
 #[pyfunction]
 fn comp(left: Layout, right: Layout, pad: bool, fix: bool) -> PyResult<Layout> {
-  Ok(Layout { native: native::comp(left.native, right.native, pad, fix) })
+  Ok(do_comp(left, right, pad, fix))
 }
 
 #[pyfunction]
@@ -85,32 +246,76 @@ fn print(doc: Document) -> PyResult<String> {
 }
 
 #[pyfunction]
-fn compile(layout: Layout) -> PyResult<Document> {
-  Ok(Document { native: native::compile(layout.native) })
+fn compile(py: Python, layout: Layout) -> PyResult<Document> {
+  let Layout { native, trace } = layout;
+  debug!("compile: solving layout {}", native);
+  let doc = py.allow_threads(|| native::compile(native));
+  debug!("compile: produced document {}", doc);
+  Ok(Document { native: doc, trace })
+}
+
+#[pyfunction]
+fn render(py: Python, doc: Document, tab: usize, width: usize) -> PyResult<String> {
+  debug!("render: doc={} tab={} width={}", doc.native, tab, width);
+  let output = py.allow_threads(|| native::render(doc.native, tab, width));
+  debug!("render: produced {} byte(s) of output", output.len());
+  Ok(output)
+}
+
+#[pyfunction]
+fn render_verbose(py: Python, doc: Document, tab: usize, width: usize) -> PyResult<(String, Vec<String>)> {
+  let mut trace = doc.trace;
+  debug!("render_verbose: doc={} tab={} width={}", doc.native, tab, width);
+  let output = py.allow_threads(|| native::render(doc.native, tab, width));
+  debug!("render_verbose: produced {} byte(s) of output", output.len());
+  measure_lines(&mut trace, &output, width);
+  Ok((output, trace))
+}
+
+#[pyfunction]
+fn set_log_level(level: String) -> PyResult<()> {
+  let filter = level.parse::<log::LevelFilter>().map_err(|err| {
+    pyo3::exceptions::PyValueError::new_err(format!("invalid log level {:?}: {}", level, err))
+  })?;
+  log::set_max_level(filter);
+  Ok(())
 }
 
 #[pyfunction]
-fn render(doc: Document, tab: usize, width: usize) -> PyResult<String> {
-  Ok(native::render(doc.native, tab, width))
+fn compile_many(py: Python, layouts: Vec<Layout>) -> PyResult<Vec<Document>> {
+  Ok(py.allow_threads(|| {
+    layouts.into_iter()
+      .map(|layout| {
+        let Layout { native, trace } = layout;
+        Document { native: native::compile(native), trace }
+      })
+      .collect()
+  }))
+}
+
+#[pyfunction]
+fn render_many(py: Python, docs: Vec<Document>, tab: usize, width: usize) -> PyResult<Vec<String>> {
+  Ok(py.allow_threads(|| {
+    docs.into_iter()
+      .map(|doc| native::render(doc.native, tab, width))
+      .collect()
+  }))
 }
 
 #[pyfunction]
 #[pyo3(signature = (input, *args))]
-fn parse(input: String, args: &PyTuple) -> PyResult<Layout> {
+fn parse(input: String, args: &Bound<'_, PyTuple>) -> PyResult<Layout> {
   let _args: Result<Vec<Box<native::Layout>>, PyErr> =
-    args.iter().map(|layout: &PyAny| -> Result<Box<native::Layout>, PyErr> {
+    args.iter().map(|layout| -> Result<Box<native::Layout>, PyErr> {
       Ok(layout.extract::<Layout>()?.native)
     }).collect();
-  Ok(Layout {
-    native: parser::parse(
-      input.as_str(),
-      &_args?
-    ).map_err(exceptions::PyValueError::new_err)?
-  })
+  Ok(leaf(
+    parser::parse(input.as_str(), &_args?).map_err(parser::ParseError::new_err)?
+  ))
 }
 
 #[pymodule]
-fn typeset(_py: Python, typeset_module: &PyModule) -> PyResult<()> {
+fn typeset(py: Python<'_>, typeset_module: &Bound<'_, PyModule>) -> PyResult<()> {
   pyo3_log::init();
   typeset_module.add_class::<Layout>()?;
   typeset_module.add_class::<Document>()?;
@@ -126,6 +331,10 @@ fn typeset(_py: Python, typeset_module: &PyModule) -> PyResult<()> {
   let _print = wrap_pyfunction!(print, typeset_module)?;
   let _compile = wrap_pyfunction!(compile, typeset_module)?;
   let _render = wrap_pyfunction!(render, typeset_module)?;
+  let _compile_many = wrap_pyfunction!(compile_many, typeset_module)?;
+  let _render_many = wrap_pyfunction!(render_many, typeset_module)?;
+  let _render_verbose = wrap_pyfunction!(render_verbose, typeset_module)?;
+  let _set_log_level = wrap_pyfunction!(set_log_level, typeset_module)?;
   let _parse = wrap_pyfunction!(parse, typeset_module)?;
   typeset_module.add_function(_null)?;
   typeset_module.add_function(_text)?;
@@ -139,6 +348,57 @@ fn typeset(_py: Python, typeset_module: &PyModule) -> PyResult<()> {
   typeset_module.add_function(_print)?;
   typeset_module.add_function(_compile)?;
   typeset_module.add_function(_render)?;
+  typeset_module.add_function(_compile_many)?;
+  typeset_module.add_function(_render_many)?;
+  typeset_module.add_function(_render_verbose)?;
+  typeset_module.add_function(_set_log_level)?;
   typeset_module.add_function(_parse)?;
+  let parser_module = wrap_pymodule!(parser::parser)(py);
+  typeset_module.add_submodule(parser_module.bind(py))?;
+  py.import_bound("sys")?
+    .getattr("modules")?
+    .set_item("typeset.parser", parser_module)?;
   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn render_verbose_reports_the_trace() {
+    Python::with_gil(|py| {
+      let nested = nest(leaf(native::text("x".to_string()))).unwrap();
+      let doc = compile(py, nested).unwrap();
+      let (_, trace) = render_verbose(py, doc, 2, 80).unwrap();
+      assert!(!trace.is_empty());
+    });
+  }
+
+  #[test]
+  fn render_verbose_does_not_mix_traces_across_documents() {
+    Python::with_gil(|py| {
+      let a = nest(leaf(native::text("a".to_string()))).unwrap();
+      let b = grp(leaf(native::text("b".to_string()))).unwrap();
+      let a_doc = compile(py, a).unwrap();
+      let b_doc = compile(py, b).unwrap();
+      let (_, a_trace) = render_verbose(py, a_doc, 2, 80).unwrap();
+      let (_, b_trace) = render_verbose(py, b_doc, 2, 80).unwrap();
+      assert!(a_trace.iter().any(|event| event.contains("nest")));
+      assert!(b_trace.iter().any(|event| event.contains("grp")));
+      assert!(!a_trace.iter().any(|event| event.contains("grp")));
+      assert!(!b_trace.iter().any(|event| event.contains("nest")));
+    });
+  }
+
+  #[test]
+  fn render_verbose_is_repeatable_on_the_same_document() {
+    Python::with_gil(|py| {
+      let layout = nest(leaf(native::text("x".to_string()))).unwrap();
+      let doc = compile(py, layout).unwrap();
+      let (_, first) = render_verbose(py, doc.clone(), 2, 80).unwrap();
+      let (_, second) = render_verbose(py, doc, 2, 80).unwrap();
+      assert_eq!(first, second);
+    });
+  }
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+use pyo3::create_exception;
+use pyo3::prelude::*;
+
+use ::typeset::{self as native};
+
+create_exception!(typeset.parser, ParseError, pyo3::exceptions::PyException);
+
+const PLACEHOLDER: char = '#';
+
+pub fn parse(input: &str, args: &[Box<native::Layout>]) -> Result<Box<native::Layout>, String> {
+  let (layout, rest) = splice(input, args)?;
+  if !rest.is_empty() {
+    return Err(format!("{} argument(s) left over after splicing", rest.len()));
+  }
+  Ok(layout)
+}
+
+pub fn splice<'a>(
+  input: &str,
+  args: &'a [Box<native::Layout>]
+) -> Result<(Box<native::Layout>, &'a [Box<native::Layout>]), String> {
+  let mut chunks = input.split(PLACEHOLDER);
+  let mut layout = native::text(chunks.next().unwrap_or("").to_string());
+  let mut remaining = args;
+  for chunk in chunks {
+    let (arg, rest) = remaining
+      .split_first()
+      .ok_or_else(|| "not enough arguments to fill the placeholders in the input".to_string())?;
+    remaining = rest;
+    layout = native::comp(layout, arg.clone(), true, false);
+    if !chunk.is_empty() {
+      layout = native::comp(layout, native::text(chunk.to_string()), true, false);
+    }
+  }
+  Ok((layout, remaining))
+}
+
+#[pyfunction]
+fn placeholder() -> PyResult<String> {
+  Ok(PLACEHOLDER.to_string())
+}
+
+#[pyfunction]
+#[pyo3(name = "parse", signature = (input, *args))]
+fn parse_py(py: Python, input: String, args: &Bound<'_, pyo3::types::PyTuple>) -> PyResult<crate::Layout> {
+  let layouts: Result<Vec<crate::Layout>, PyErr> =
+    args.iter().map(|layout| layout.extract::<crate::Layout>()).collect();
+  let layouts = layouts?;
+  let native_args: Vec<Box<native::Layout>> = layouts.iter().map(|layout| layout.native.clone()).collect();
+  let mut trace: Vec<String> = layouts.into_iter().flat_map(|layout| layout.trace).collect();
+  let native = py.allow_threads(|| parse(input.as_str(), &native_args))
+    .map_err(ParseError::new_err)?;
+  crate::record(&mut trace, format!("parse: spliced {} argument(s) into {:?}", native_args.len(), input));
+  Ok(crate::Layout { native, trace })
+}
+
+#[pyfunction]
+#[pyo3(name = "splice", signature = (input, *args))]
+fn splice_py(
+  py: Python,
+  input: String,
+  args: &Bound<'_, pyo3::types::PyTuple>
+) -> PyResult<(crate::Layout, Vec<crate::Layout>)> {
+  let layouts: Result<Vec<crate::Layout>, PyErr> =
+    args.iter().map(|layout| layout.extract::<crate::Layout>()).collect();
+  let layouts = layouts?;
+  let native_args: Vec<Box<native::Layout>> = layouts.iter().map(|layout| layout.native.clone()).collect();
+  let (native, rest) = py.allow_threads(|| splice(input.as_str(), &native_args))
+    .map_err(ParseError::new_err)?;
+  let consumed = native_args.len() - rest.len();
+  let mut leftover = layouts;
+  let mut trace: Vec<String> = leftover.drain(..consumed).flat_map(|layout| layout.trace).collect();
+  crate::record(&mut trace, format!("splice: spliced {} argument(s) into {:?}, {} left over", consumed, input, leftover.len()));
+  Ok((crate::Layout { native, trace }, leftover))
+}
+
+#[pymodule]
+pub fn parser(py: Python<'_>, module: &Bound<'_, PyModule>) -> PyResult<()> {
+  module.add("ParseError", py.get_type::<ParseError>())?;
+  module.add_function(wrap_pyfunction!(parse_py, module)?)?;
+  module.add_function(wrap_pyfunction!(splice_py, module)?)?;
+  module.add_function(wrap_pyfunction!(placeholder, module)?)?;
+  Ok(())
+}